@@ -1,44 +1,220 @@
-use zed_extension_api::{self as zed, Command, LanguageServerId, Result, Worktree};
+use std::fs;
+use zed_extension_api::{
+    self as zed, settings::LspSettings, Architecture, Command, LanguageServerId, Os, Result,
+    Worktree,
+};
 
-struct KukichaExtension;
+const KUKICHA_LSP_REPO: &str = "duber000/kukicha-lsp";
+const SERVER_NAME: &str = "kukicha-lsp";
+
+/// Second language server id this extension registers in `extension.toml`,
+/// for companion/template files that embed Kukicha. Both ids are served by
+/// the same `kukicha-lsp` binary; only the startup args differ so the server
+/// knows which grammar to load for the associated language.
+const KUKICHA_TEMPLATE_LSP_ID: &str = "kukicha-template-lsp";
+
+/// Default startup args for a given language server id, used when the user
+/// hasn't overridden `lsp.<server_id>.binary.arguments` in their settings.
+fn default_args_for(language_server_id: &LanguageServerId) -> Vec<String> {
+    match language_server_id.as_ref() {
+        KUKICHA_TEMPLATE_LSP_ID => vec!["--lang".to_string(), "kukicha-template".to_string()],
+        _ => vec![],
+    }
+}
+
+struct KukichaExtension {
+    cached_binary_path: Option<String>,
+}
+
+impl KukichaExtension {
+    fn language_server_binary_path(
+        &mut self,
+        language_server_id: &LanguageServerId,
+    ) -> Result<String> {
+        if let Some(path) = &self.cached_binary_path {
+            if fs::metadata(path).is_ok_and(|stat| stat.is_file()) {
+                return Ok(path.clone());
+            }
+        }
+
+        zed::set_language_server_installation_status(
+            language_server_id,
+            &zed::LanguageServerInstallationStatus::CheckingForUpdate,
+        );
+
+        let release = zed::latest_github_release(
+            KUKICHA_LSP_REPO,
+            zed::GithubReleaseOptions {
+                require_assets: true,
+                pre_release: false,
+            },
+        )?;
+
+        let (os, arch) = zed::current_platform();
+        let os_str = match os {
+            Os::Mac => "mac",
+            Os::Linux => "linux",
+            Os::Windows => "windows",
+        };
+        let arch_str = match arch {
+            Architecture::Aarch64 => "aarch64",
+            Architecture::X86 => "x86",
+            Architecture::X8664 => "x86_64",
+        };
+
+        let asset = release
+            .assets
+            .iter()
+            .find(|asset| asset.name.contains(os_str) && asset.name.contains(arch_str))
+            .ok_or_else(|| format!("no asset found matching target {os_str}-{arch_str}"))?;
+
+        let version_dir = format!("kukicha-lsp-{}", release.version);
+        let binary_name = if matches!(os, Os::Windows) {
+            "kukicha-lsp.exe"
+        } else {
+            "kukicha-lsp"
+        };
+        let binary_path = format!("{version_dir}/{binary_name}");
+
+        if !fs::metadata(&binary_path).is_ok_and(|stat| stat.is_file()) {
+            zed::set_language_server_installation_status(
+                language_server_id,
+                &zed::LanguageServerInstallationStatus::Downloading,
+            );
+
+            // Archive types (`Zip`/`GzipTar`) extract into a directory, so they
+            // take `version_dir`. `Gzip`/`Uncompressed` write the (decompressed)
+            // bytes straight to the given path as a single file, so they need
+            // the binary's own path instead.
+            let (file_type, download_path) = if asset.name.ends_with(".zip") {
+                (zed::DownloadedFileType::Zip, version_dir.as_str())
+            } else if asset.name.ends_with(".tar.gz") || asset.name.ends_with(".tgz") {
+                (zed::DownloadedFileType::GzipTar, version_dir.as_str())
+            } else if asset.name.ends_with(".gz") {
+                (zed::DownloadedFileType::Gzip, binary_path.as_str())
+            } else {
+                (zed::DownloadedFileType::Uncompressed, binary_path.as_str())
+            };
+
+            zed::download_file(&asset.download_url, download_path, file_type)
+                .map_err(|e| format!("failed to download kukicha-lsp: {e}"))?;
+
+            zed::make_file_executable(&binary_path)?;
+
+            let entries =
+                fs::read_dir(".").map_err(|e| format!("failed to list extension work dir: {e}"))?;
+            for entry in entries {
+                let entry = entry.map_err(|e| format!("failed to read directory entry: {e}"))?;
+                if entry.file_name().to_str() != Some(&version_dir) {
+                    fs::remove_dir_all(entry.path()).ok();
+                }
+            }
+        }
+
+        zed::set_language_server_installation_status(
+            language_server_id,
+            &zed::LanguageServerInstallationStatus::None,
+        );
+
+        self.cached_binary_path = Some(binary_path.clone());
+        Ok(binary_path)
+    }
+}
 
 impl zed::Extension for KukichaExtension {
     fn new() -> Self {
-        KukichaExtension
+        KukichaExtension {
+            cached_binary_path: None,
+        }
     }
 
     fn language_server_command(
         &mut self,
-        _language_server_id: &LanguageServerId,
+        language_server_id: &LanguageServerId,
         worktree: &Worktree,
     ) -> Result<Command> {
+        let lsp_settings = LspSettings::for_worktree(language_server_id.as_ref(), worktree).ok();
+        let binary_settings = lsp_settings
+            .as_ref()
+            .and_then(|settings| settings.binary.as_ref());
+        let default_args = default_args_for(language_server_id);
+
+        // Explicit binary path in `lsp.<server_id>.binary.path` settings wins over
+        // everything else, since the user asked for it directly.
+        if let Some(path) = binary_settings.and_then(|binary| binary.path.clone()) {
+            let args = binary_settings
+                .and_then(|binary| binary.arguments.clone())
+                .unwrap_or(default_args);
+            return Ok(Command {
+                command: path,
+                args,
+                env: worktree.shell_env(),
+            });
+        }
+
+        // A binary resolved from the env var or PATH is user-managed, but it's
+        // still addressed by `language_server_id`, so `default_args_for` still
+        // applies unless the user configured their own arguments — otherwise
+        // a server id like `kukicha-template-lsp` would never get the flag
+        // that selects its grammar.
+        let args = binary_settings
+            .and_then(|binary| binary.arguments.clone())
+            .unwrap_or(default_args);
+
         // KUKICHA_LSP_PATH override (may not be available in WASM sandbox)
         if let Ok(explicit_path) = std::env::var("KUKICHA_LSP_PATH") {
             let explicit_path = explicit_path.trim();
             if !explicit_path.is_empty() {
                 return Ok(Command {
                     command: explicit_path.to_string(),
-                    args: vec![],
+                    args,
                     env: worktree.shell_env(),
                 });
             }
         }
 
-        let path = worktree
-            .which("kukicha-lsp")
-            .ok_or_else(|| {
-                "kukicha-lsp not found. Set KUKICHA_LSP_PATH or install with: make install-lsp"
-            })?;
+        if let Some(path) = worktree.which(SERVER_NAME) {
+            return Ok(Command {
+                command: path,
+                args,
+                env: worktree.shell_env(),
+            });
+        }
+
+        let path = self.language_server_binary_path(language_server_id)?;
 
         Ok(Command {
             command: path,
-            args: vec![],
+            args,
             env: worktree.shell_env(),
         })
     }
+
+    fn language_server_workspace_configuration(
+        &mut self,
+        language_server_id: &LanguageServerId,
+        worktree: &Worktree,
+    ) -> Result<Option<zed::serde_json::Value>> {
+        let settings = LspSettings::for_worktree(language_server_id.as_ref(), worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.settings)
+            .unwrap_or_default();
+
+        Ok(Some(settings))
+    }
+
+    fn language_server_initialization_options(
+        &mut self,
+        language_server_id: &LanguageServerId,
+        worktree: &Worktree,
+    ) -> Result<Option<zed::serde_json::Value>> {
+        let options = LspSettings::for_worktree(language_server_id.as_ref(), worktree)
+            .ok()
+            .and_then(|lsp_settings| lsp_settings.initialization_options)
+            .unwrap_or_default();
+
+        Ok(Some(options))
+    }
 }
 
 zed::register_extension!(KukichaExtension);
-// test
-// test
-// test